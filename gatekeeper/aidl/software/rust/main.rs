@@ -27,7 +27,9 @@
 use gk_hal::channel::SerializedChannel;
 use log::{error, info, warn};
 use std::fs;
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ta_worker::Supervisor;
 
 /// Location of Gatekeeper failure records.  This directory must exist for this implementation of
 /// Gatekeeper to run.
@@ -95,6 +97,10 @@ fn inner_main() -> Result<(), HalServiceError> {
 
     // Create a TA in-process, which acts as a local channel for communication.
     let channel = Arc::new(Mutex::new(LocalInsecureTa::new(dir)));
+    // Gatekeeper has no startup handshake of its own (unlike KeyMint's boot/HAL info), so mark it
+    // done immediately: every request a client sends from here on (e.g. `enroll`) must never be
+    // replayed against a TA rebuilt after a restart.
+    channel.lock().unwrap().mark_handshake_done();
 
     let ss_service = gk_hal::sharedsecret::SharedSecretService::new_as_binder(channel.clone());
     let service_name = format!("{SECRET_SERVICE}/{SS_INSTANCE}");
@@ -115,40 +121,48 @@ fn inner_main() -> Result<(), HalServiceError> {
 }
 
 /// Implementation of the Gatekeeper TA that runs locally in-process (and which is therefore
-/// insecure).
-#[derive(Debug)]
+/// insecure). The TA thread is supervised: if it dies, it is transparently rebuilt rather than
+/// taking the whole HAL service down with it.
 pub struct LocalInsecureTa {
-    in_tx: mpsc::Sender<Vec<u8>>,
-    out_rx: mpsc::Receiver<Vec<u8>>,
+    supervisor: Supervisor<gk_ta::GatekeeperTa>,
 }
 
 impl LocalInsecureTa {
+    /// How long to wait for the TA to reply before treating it as wedged.
+    const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
     /// Create a new (insecure) instance.
     pub fn new(dir: std::path::PathBuf) -> Self {
-        // Create a pair of channels to communicate with the TA thread.
-        let (in_tx, in_rx) = mpsc::channel();
-        let (out_tx, out_rx) = mpsc::channel();
-
-        // The TA code expects to run single threaded, so spawn a thread to run it in.
-        std::thread::spawn(move || {
-            let mut ta = gk_ta_nonsecure::build_ta(&dir);
-            loop {
-                let req_data: Vec<u8> = in_rx.recv().expect("failed to receive next req");
-                let rsp_data = ta.process(&req_data);
-                out_tx.send(rsp_data).expect("failed to send out rsp");
-            }
-        });
-        Self { in_tx, out_rx }
+        let supervisor = Supervisor::new(
+            Self::MAX_SIZE,
+            Self::CALL_TIMEOUT,
+            move || gk_ta_nonsecure::build_ta(&dir),
+            |ta, req_data| ta.process(req_data),
+            ta_worker::opcode_of,
+        );
+        Self { supervisor }
+    }
+
+    /// Mark the startup handshake as complete; see [`Supervisor::mark_handshake_done`].
+    pub fn mark_handshake_done(&mut self) {
+        self.supervisor.mark_handshake_done();
     }
 }
 
 impl SerializedChannel for LocalInsecureTa {
-    const MAX_SIZE: usize = usize::MAX;
+    const MAX_SIZE: usize = 64 * 1024;
 
     fn execute(&mut self, req_data: &[u8]) -> binder::Result<Vec<u8>> {
-        self.in_tx
-            .send(req_data.to_vec())
-            .expect("failed to send in request");
-        Ok(self.out_rx.recv().expect("failed to receive response"))
+        self.supervisor.call(req_data).map_err(|e| match e {
+            ta_worker::Error::RequestTooLarge => {
+                binder::Status::new_exception(binder::ExceptionCode::ILLEGAL_ARGUMENT, None)
+            }
+            ta_worker::Error::Timeout => {
+                binder::Status::from(binder::StatusCode::TIMED_OUT)
+            }
+            ta_worker::Error::Disconnected | ta_worker::Error::Desynced => {
+                binder::Status::from(binder::StatusCode::DEAD_OBJECT)
+            }
+        })
     }
 }
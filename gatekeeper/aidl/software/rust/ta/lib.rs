@@ -31,16 +31,34 @@ pub fn build_ta(dir: &std::path::Path) -> gk_ta::GatekeeperTa {
     let clock = StdClock::default();
     let auth_key = traits::ExplicitAuthKey::new(Box::new(boring::HmacSha256));
 
-    // Store failure records on the filesystem under the given directory. This is not secure.
-    let std_fs = StdFilesystem {
-        dir: std::path::PathBuf::from(dir),
-    };
+    // Store failure records on the filesystem under the given directory, wrapped with an
+    // HMAC chain so that deleting a record or restoring an older copy of a record is detected as
+    // tampering rather than silently resetting the lockout; restoring the whole directory,
+    // manifest included, is not detected (see `AuthenticatedFilesystem`), and with an AES-256-GCM layer so the records are confidential
+    // and tamper-evident at rest rather than plaintext (see `EncryptedFilesystem`).
+    let std_fs = EncryptedFilesystem::new(
+        AuthenticatedFilesystem::new(
+            StdFilesystem {
+                dir: std::path::PathBuf::from(dir),
+            },
+            Box::new(FixedStorageKey(STORAGE_HMAC_KEY)),
+        ),
+        Box::new(FixedStorageKey(STORAGE_ROOT_KEY)),
+    );
 
     // Pre-shared key of all-zeros for `ISharedSecret` agreement, matching:
     // - `kFakeAgreementKey` in `system/keymaster/km_openssl/soft_keymaster_enforcement.cpp`
     // - `Keys::kak` in `hardware/interfaces/security/keymint/aidl/default/ta/soft.rs`
     const SS_PRESHARED_KEY: traits::Aes256Key = [0; 32];
 
+    // Fixed HMAC key protecting the failure-record chain for the insecure build. A real
+    // implementation must source this from hardware-bound key storage.
+    const STORAGE_HMAC_KEY: [u8; 32] = [0x5a; 32];
+
+    // Fixed root key for the at-rest encryption layer protecting failure records for the
+    // insecure build. A real implementation must source this from hardware-bound key storage.
+    const STORAGE_ROOT_KEY: [u8; 32] = [0xa5; 32];
+
     let imp = traits::Implementation {
         rng: Box::new(rng),
         clock: Box::new(clock),
@@ -92,6 +110,24 @@ impl traits::PasswordKeyRetrieval for NonsecurePasswordKey {
     }
 }
 
+/// Source of a 256-bit key used to protect failure records at rest. Mirrors
+/// `traits::PasswordKeyRetrieval`: the insecure build always returns a fixed key, but a real
+/// implementation can instead source one from hardware-bound key storage, so that
+/// `AuthenticatedFilesystem` and `EncryptedFilesystem` stay pluggable the same way the rest of
+/// `Implementation` is.
+trait StorageKeyRetrieval: Send + Sync {
+    fn key(&self) -> Result<[u8; 32], Error>;
+}
+
+/// Fixed key source, for the insecure build.
+struct FixedStorageKey([u8; 32]);
+
+impl StorageKeyRetrieval for FixedStorageKey {
+    fn key(&self) -> Result<[u8; 32], Error> {
+        Ok(self.0)
+    }
+}
+
 /// Representation of a flat directory for files.
 struct StdFilesystem {
     dir: std::path::PathBuf,
@@ -156,3 +192,462 @@ impl Iterator for StdDirIterator {
         }
     }
 }
+
+/// Name of the on-disk manifest file used by [`AuthenticatedFilesystem`]. Hidden from
+/// [`AuthenticatedFilesystem::list`] so callers only ever see the failure records they wrote.
+const MANIFEST_FILENAME: &str = ".manifest";
+
+/// Length, in bytes, of the per-record header that [`AuthenticatedFilesystem`] prepends to every
+/// stored file: a little-endian generation counter, the chain MAC in effect before this record
+/// was written, and this record's own MAC.
+const RECORD_HEADER_LEN: usize = 8 + 32 + 32;
+
+/// An authenticated wrapper around another [`SecureFilesystem`] that detects *partial* tampering
+/// with failure/throttle records.
+///
+/// `StdFilesystem` alone writes failure/throttle records as plaintext files, which a root user
+/// can delete or replace with an older copy to reset the failure count and allow infinite retries.
+/// `AuthenticatedFilesystem` closes part of that hole: every record is stored with a generation
+/// counter and is linked into an append-only HMAC chain, and a small authenticated manifest
+/// tracks the highest generation seen for every filename. A record whose generation has gone
+/// backwards relative to the manifest, or whose MAC doesn't check out, is rejected as tampered
+/// (and treated as maximally throttled) rather than accepted as if it were simply absent -- and a
+/// record that's gone missing while the manifest still remembers a generation for it is treated
+/// the same way, rather than as "never enrolled".
+///
+/// This is **not** a true Weaver-style hardware counter, and does not provide the rollback
+/// resistance that requires: the generation floor lives in `.manifest`, a file under the very
+/// same directory this type protects. Reverting or deleting a record *without* its manifest entry
+/// is caught, because the on-disk manifest still remembers the higher generation. But an attacker
+/// who can restore a full, internally-consistent snapshot of the directory -- record and manifest
+/// together, from the same point in time -- rolls the floor back in lockstep with the record, and
+/// this type cannot detect that (see `whole_directory_snapshot_restore_is_not_detected` below). A
+/// real implementation that needs that guarantee must source the generation floor from storage
+/// outside this directory (e.g. a Weaver/RPMB hardware counter), which this insecure, directory-
+/// only build does not have access to.
+struct AuthenticatedFilesystem<F> {
+    inner: F,
+    key: Box<dyn StorageKeyRetrieval>,
+}
+
+/// The authenticated manifest: the generation/MAC chain state, plus the highest generation at
+/// which each filename has been written.
+struct Manifest {
+    generation: u64,
+    chain_mac: [u8; 32],
+    file_generation: std::collections::BTreeMap<String, u64>,
+}
+
+impl Manifest {
+    fn empty() -> Self {
+        Self { generation: 0, chain_mac: [0; 32], file_generation: std::collections::BTreeMap::new() }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.generation.to_le_bytes());
+        buf.extend_from_slice(&self.chain_mac);
+        buf.extend_from_slice(&(self.file_generation.len() as u32).to_le_bytes());
+        for (name, generation) in &self.file_generation {
+            buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            buf.extend_from_slice(name.as_bytes());
+            buf.extend_from_slice(&generation.to_le_bytes());
+        }
+        buf
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 8 + 32 + 4 {
+            return None;
+        }
+        let generation = u64::from_le_bytes(buf[0..8].try_into().ok()?);
+        let chain_mac: [u8; 32] = buf[8..40].try_into().ok()?;
+        let count = u32::from_le_bytes(buf[40..44].try_into().ok()?);
+        let mut pos = 44;
+        let mut file_generation = std::collections::BTreeMap::new();
+        for _ in 0..count {
+            let name_len = buf.get(pos..pos + 2)?;
+            let name_len = u16::from_le_bytes(name_len.try_into().ok()?) as usize;
+            pos += 2;
+            let name = std::str::from_utf8(buf.get(pos..pos + name_len)?).ok()?.to_string();
+            pos += name_len;
+            let generation = u64::from_le_bytes(buf.get(pos..pos + 8)?.try_into().ok()?);
+            pos += 8;
+            file_generation.insert(name, generation);
+        }
+        Some(Self { generation, chain_mac, file_generation })
+    }
+}
+
+impl<F: SecureFilesystem> AuthenticatedFilesystem<F> {
+    fn new(inner: F, key: Box<dyn StorageKeyRetrieval>) -> Self {
+        Self { inner, key }
+    }
+
+    /// Load and authenticate the manifest, defaulting to an empty one if it has never been
+    /// written. Any manifest that fails to parse or authenticate is treated as a tamper attempt.
+    fn load_manifest(&self) -> Result<Manifest, Error> {
+        let stored = match self.inner.read(MANIFEST_FILENAME) {
+            Ok(data) => data,
+            Err(Error::NotFound) => return Ok(Manifest::empty()),
+            Err(e) => return Err(e),
+        };
+        if stored.len() < 32 {
+            error!("manifest is too short to contain a MAC");
+            return Err(Error::Internal);
+        }
+        let (body, mac) = stored.split_at(stored.len() - 32);
+        let key = self.key.key()?;
+        if !hmac_matches(&key, b"manifest", &[body], mac) {
+            error!("manifest MAC does not match; treating store as tampered");
+            return Err(Error::Internal);
+        }
+        Manifest::from_bytes(body).ok_or_else(|| {
+            error!("manifest failed to parse despite a valid MAC");
+            Error::Internal
+        })
+    }
+
+    fn store_manifest(&self, manifest: &Manifest) -> Result<(), Error> {
+        let body = manifest.to_bytes();
+        let key = self.key.key()?;
+        let mac = compute_hmac(&key, b"manifest", &[&body]);
+        let mut stored = body;
+        stored.extend_from_slice(&mac);
+        self.inner.write(MANIFEST_FILENAME, &stored)
+    }
+}
+
+impl<F: SecureFilesystem> SecureFilesystem for AuthenticatedFilesystem<F> {
+    type Iter = std::iter::Filter<F::Iter, fn(&String) -> bool>;
+
+    fn read(&self, filename: &str) -> Result<Vec<u8>, Error> {
+        let manifest = self.load_manifest()?;
+        let high_water = manifest.file_generation.get(filename).copied();
+        let stored = match self.inner.read(filename) {
+            Ok(stored) => stored,
+            // The manifest has never seen this filename written, so there is genuinely nothing
+            // to read yet (e.g. first boot, before the first failure record is written).
+            Err(Error::NotFound) if high_water.is_none() => return Err(Error::NotFound),
+            // The manifest recorded a generation for `filename`, but the record itself is gone.
+            // That's exactly the attack this type exists to close (root deletes the file to
+            // reset failure counts), so it must be treated as tampering, not as "never enrolled".
+            Err(Error::NotFound) => {
+                error!(
+                    "record {filename} is missing but the manifest recorded generation \
+                     {high_water:?} for it; treating the deletion as tampering"
+                );
+                return Err(Error::Internal);
+            }
+            Err(e) => return Err(e),
+        };
+        if stored.len() < RECORD_HEADER_LEN {
+            error!("record {filename} is too short to contain its authentication header");
+            return Err(Error::Internal);
+        }
+        let (header, data) = stored.split_at(RECORD_HEADER_LEN);
+        let generation = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let prev_mac = &header[8..40];
+        let mac = &header[40..72];
+
+        let key = self.key.key()?;
+        if !hmac_matches(&key, b"record", &[&header[0..8], filename.as_bytes(), data, prev_mac], mac) {
+            error!("record {filename} failed MAC verification; treating it as tampered");
+            return Err(Error::Internal);
+        }
+        let high_water = high_water.unwrap_or(0);
+        if generation < high_water {
+            error!(
+                "record {filename} is at generation {generation}, below the high-water mark \
+                 {high_water}; treating it as a rolled-back snapshot"
+            );
+            return Err(Error::Internal);
+        }
+        Ok(data.to_vec())
+    }
+
+    fn write(&self, filename: &str, data: &[u8]) -> Result<(), Error> {
+        let mut manifest = self.load_manifest()?;
+        let generation = manifest.generation + 1;
+        let generation_bytes = generation.to_le_bytes();
+        let prev_mac = manifest.chain_mac;
+        let key = self.key.key()?;
+        let mac = compute_hmac(&key, b"record", &[&generation_bytes, filename.as_bytes(), data, &prev_mac]);
+
+        let mut stored = Vec::with_capacity(RECORD_HEADER_LEN + data.len());
+        stored.extend_from_slice(&generation_bytes);
+        stored.extend_from_slice(&prev_mac);
+        stored.extend_from_slice(&mac);
+        stored.extend_from_slice(data);
+        self.inner.write(filename, &stored)?;
+
+        manifest.generation = generation;
+        manifest.chain_mac = mac;
+        manifest.file_generation.insert(filename.to_string(), generation);
+        self.store_manifest(&manifest)
+    }
+
+    fn delete(&self, filename: &str) -> Result<(), Error> {
+        // Deliberately leave the manifest's high-water mark for `filename` in place: if the file
+        // is later recreated, its new generation (always strictly increasing) is still compared
+        // against the mark it had before deletion.
+        self.inner.delete(filename)
+    }
+
+    fn list(&self) -> Result<Self::Iter, Error> {
+        fn is_not_manifest(name: &String) -> bool {
+            name != MANIFEST_FILENAME
+        }
+        Ok(self.inner.list()?.filter(is_not_manifest as fn(&String) -> bool))
+    }
+}
+
+/// Compute `HMAC-SHA256(key, label || chunks.concat())`.
+fn compute_hmac(key: &[u8; 32], label: &[u8], chunks: &[&[u8]]) -> [u8; 32] {
+    let msg: Vec<u8> = chunks.iter().copied().flatten().copied().collect();
+    boring::hmac_sha256(key, label, &msg)
+}
+
+/// Check whether `HMAC-SHA256(key, label || chunks.concat())` equals `expected`, in constant time.
+fn hmac_matches(key: &[u8; 32], label: &[u8], chunks: &[&[u8]], expected: &[u8]) -> bool {
+    let actual = compute_hmac(key, label, chunks);
+    actual.len() == expected.len()
+        && actual.iter().zip(expected).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Length, in bytes, of the random nonce [`EncryptedFilesystem`] generates for every write.
+const GCM_NONCE_LEN: usize = 12;
+
+/// An encryption-at-rest wrapper around another [`SecureFilesystem`]: every stored file is
+/// confidentiality- and integrity-protected with AES-256-GCM, using a per-file data-encryption
+/// key derived from a single root key by HKDF-SHA256 (the filename is the HKDF info, so no two
+/// files share a key). Modelled on how disk-encryption key storage wraps blobs.
+struct EncryptedFilesystem<F> {
+    inner: F,
+    root_key: Box<dyn StorageKeyRetrieval>,
+}
+
+impl<F: SecureFilesystem> EncryptedFilesystem<F> {
+    fn new(inner: F, root_key: Box<dyn StorageKeyRetrieval>) -> Self {
+        Self { inner, root_key }
+    }
+
+    /// Derive this file's data-encryption key from the root key, using the filename as the
+    /// HKDF-SHA256 info parameter so that every file gets an independent key.
+    fn data_key(&self, filename: &str) -> Result<[u8; 32], Error> {
+        let root_key = self.root_key.key()?;
+        Ok(boring::hkdf_sha256(&root_key, filename.as_bytes(), 32)
+            .try_into()
+            .expect("HKDF-SHA256 output is always 32 bytes when 32 bytes are requested"))
+    }
+}
+
+impl<F: SecureFilesystem> SecureFilesystem for EncryptedFilesystem<F> {
+    type Iter = F::Iter;
+
+    fn read(&self, filename: &str) -> Result<Vec<u8>, Error> {
+        let stored = self.inner.read(filename)?;
+        if stored.len() < GCM_NONCE_LEN {
+            error!("encrypted record {filename} is too short to contain a nonce");
+            return Err(Error::Internal);
+        }
+        let (nonce, ciphertext) = stored.split_at(GCM_NONCE_LEN);
+        let key = self.data_key(filename)?;
+        boring::aes256_gcm_open(&key, nonce, filename.as_bytes(), ciphertext).map_err(|_| {
+            error!("encrypted record {filename} failed GCM tag verification");
+            Error::Internal
+        })
+    }
+
+    fn write(&self, filename: &str, data: &[u8]) -> Result<(), Error> {
+        let mut nonce = [0u8; GCM_NONCE_LEN];
+        fill_random(&mut nonce);
+
+        let key = self.data_key(filename)?;
+        let ciphertext = boring::aes256_gcm_seal(&key, &nonce, filename.as_bytes(), data);
+
+        let mut stored = Vec::with_capacity(GCM_NONCE_LEN + ciphertext.len());
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&ciphertext);
+        self.inner.write(filename, &stored)
+    }
+
+    fn delete(&self, filename: &str) -> Result<(), Error> {
+        self.inner.delete(filename)
+    }
+
+    fn list(&self) -> Result<Self::Iter, Error> {
+        self.inner.list()
+    }
+}
+
+/// Fill `buf` with random bytes from the kernel CSPRNG, as used for GCM nonces.
+fn fill_random(buf: &mut [u8]) {
+    // SAFETY: `buf` is a valid, exclusively-borrowed byte slice of its own stated length.
+    let rc = unsafe { libc::getrandom(buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+    if rc as usize != buf.len() {
+        // `getrandom(2)` only returns short on an interrupting signal for requests this small;
+        // retry once before giving up, rather than risk nonce reuse with a partially-filled
+        // buffer.
+        // SAFETY: as above.
+        let rc = unsafe { libc::getrandom(buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        assert_eq!(rc as usize, buf.len(), "failed to fill {} random bytes", buf.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    /// An in-memory [`SecureFilesystem`], for exercising the authentication/encryption wrapper
+    /// layers without touching disk.
+    #[derive(Default)]
+    struct MemFilesystem {
+        files: RefCell<BTreeMap<String, Vec<u8>>>,
+    }
+
+    impl SecureFilesystem for MemFilesystem {
+        type Iter = std::vec::IntoIter<String>;
+
+        fn read(&self, filename: &str) -> Result<Vec<u8>, Error> {
+            self.files.borrow().get(filename).cloned().ok_or(Error::NotFound)
+        }
+        fn write(&self, filename: &str, data: &[u8]) -> Result<(), Error> {
+            self.files.borrow_mut().insert(filename.to_string(), data.to_vec());
+            Ok(())
+        }
+        fn delete(&self, filename: &str) -> Result<(), Error> {
+            self.files.borrow_mut().remove(filename).map(|_| ()).ok_or(Error::NotFound)
+        }
+        fn list(&self) -> Result<Self::Iter, Error> {
+            Ok(self.files.borrow().keys().cloned().collect::<Vec<_>>().into_iter())
+        }
+    }
+
+    fn authenticated_store() -> AuthenticatedFilesystem<MemFilesystem> {
+        AuthenticatedFilesystem::new(MemFilesystem::default(), Box::new(FixedStorageKey([0x42; 32])))
+    }
+
+    #[test]
+    fn read_after_write_round_trips() {
+        let fs = authenticated_store();
+        fs.write("rec", b"hello").unwrap();
+        assert_eq!(fs.read("rec").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn never_written_record_is_just_not_found() {
+        let fs = authenticated_store();
+        assert!(matches!(fs.read("never-written"), Err(Error::NotFound)));
+    }
+
+    #[test]
+    fn deleting_a_written_record_is_detected_as_tampering() {
+        let fs = authenticated_store();
+        fs.write("rec", b"hello").unwrap();
+        fs.delete("rec").unwrap();
+        // The manifest still remembers a high-water generation for "rec", so its disappearance
+        // must be reported as tampering, not treated as though it had never been written.
+        assert!(matches!(fs.read("rec"), Err(Error::Internal)));
+    }
+
+    #[test]
+    fn flipping_a_stored_byte_fails_the_mac() {
+        let fs = authenticated_store();
+        fs.write("rec", b"hello").unwrap();
+        {
+            let mut files = fs.inner.files.borrow_mut();
+            let stored = files.get_mut("rec").unwrap();
+            let last = stored.len() - 1;
+            stored[last] ^= 0xff;
+        }
+        assert!(matches!(fs.read("rec"), Err(Error::Internal)));
+    }
+
+    #[test]
+    fn restoring_an_older_record_snapshot_is_rejected_as_rollback() {
+        let fs = authenticated_store();
+        fs.write("rec", b"first").unwrap();
+        let snapshot = fs.inner.files.borrow().get("rec").unwrap().clone();
+
+        fs.write("rec", b"second").unwrap();
+        assert_eq!(fs.read("rec").unwrap(), b"second");
+
+        // Restore only the record to its first-generation snapshot; the manifest (which still
+        // has the newer high-water mark) is left as-is, as it would be if an attacker could
+        // replace a single record file but not the whole authenticated store consistently.
+        fs.inner.files.borrow_mut().insert("rec".to_string(), snapshot);
+        assert!(matches!(fs.read("rec"), Err(Error::Internal)));
+    }
+
+    #[test]
+    fn whole_directory_snapshot_restore_is_not_detected() {
+        let fs = authenticated_store();
+        fs.write("rec", b"first").unwrap();
+        let snapshot_record = fs.inner.files.borrow().get("rec").unwrap().clone();
+        let snapshot_manifest = fs.inner.files.borrow().get(MANIFEST_FILENAME).unwrap().clone();
+
+        fs.write("rec", b"second").unwrap();
+        assert_eq!(fs.read("rec").unwrap(), b"second");
+
+        // Restore *both* the record and the manifest to their first-generation snapshot, as an
+        // attacker with a full backup of the directory could. The generation floor lives in the
+        // manifest, which was rolled back right along with the record it's meant to police, so
+        // they're still consistent with each other and this is accepted -- a known limitation of
+        // this insecure, directory-only build (see `AuthenticatedFilesystem`'s doc comment), not
+        // the behavior of a real Weaver-backed implementation.
+        {
+            let mut files = fs.inner.files.borrow_mut();
+            files.insert("rec".to_string(), snapshot_record);
+            files.insert(MANIFEST_FILENAME.to_string(), snapshot_manifest);
+        }
+        assert_eq!(fs.read("rec").unwrap(), b"first");
+    }
+
+    #[test]
+    fn corrupted_manifest_is_detected() {
+        let fs = authenticated_store();
+        fs.write("rec", b"hello").unwrap();
+        {
+            let mut files = fs.inner.files.borrow_mut();
+            let stored = files.get_mut(MANIFEST_FILENAME).unwrap();
+            let last = stored.len() - 1;
+            stored[last] ^= 0xff;
+        }
+        assert!(matches!(fs.read("rec"), Err(Error::Internal)));
+    }
+
+    fn encrypted_store() -> EncryptedFilesystem<MemFilesystem> {
+        EncryptedFilesystem::new(MemFilesystem::default(), Box::new(FixedStorageKey([0x99; 32])))
+    }
+
+    #[test]
+    fn encrypted_read_after_write_round_trips() {
+        let fs = encrypted_store();
+        fs.write("rec", b"super secret").unwrap();
+        assert_eq!(fs.read("rec").unwrap(), b"super secret");
+    }
+
+    #[test]
+    fn encrypted_ciphertext_is_not_stored_in_the_clear() {
+        let fs = encrypted_store();
+        fs.write("rec", b"super secret").unwrap();
+        let stored = fs.inner.files.borrow().get("rec").unwrap().clone();
+        assert!(!stored.windows(b"super secret".len()).any(|w| w == b"super secret"));
+    }
+
+    #[test]
+    fn flipping_a_ciphertext_byte_fails_the_gcm_tag() {
+        let fs = encrypted_store();
+        fs.write("rec", b"super secret").unwrap();
+        {
+            let mut files = fs.inner.files.borrow_mut();
+            let stored = files.get_mut("rec").unwrap();
+            let last = stored.len() - 1;
+            stored[last] ^= 0xff;
+        }
+        assert!(matches!(fs.read("rec"), Err(Error::Internal)));
+    }
+}
@@ -0,0 +1,352 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Supervision of a [`TaWorker`], so a panicked TA thread degrades to a restart instead of taking
+//! the whole HAL service down with it.
+//!
+//! Previously, if the spawned TA thread panicked, the channel it had been communicating over
+//! closed, and the next `recv()` on it panicked in turn -- there was no diagnostics and no
+//! recovery. [`Supervisor`] instead detects the closed channel, dumps a small ring buffer of
+//! recent traffic (opcodes and sizes, never payloads) to logcat for a breadcrumb trail, rebuilds
+//! the TA, replays the handshake requests the caller marked with [`Supervisor::mark_handshake_done`]
+//! (e.g. boot info / HAL info), and retries the call that uncovered the fault -- so the caller
+//! only ever sees an [`Error`], never a panic.
+
+use crate::{Error, TaWorker};
+use log::{error, warn};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Number of recent request/response pairs retained for postmortem diagnostics.
+const RING_CAPACITY: usize = 32;
+
+/// Upper bound on the number of handshake requests recorded for replay, as a backstop against a
+/// caller that never calls [`Supervisor::mark_handshake_done`].
+const HANDSHAKE_CAPACITY: usize = 8;
+
+#[derive(Clone, Copy)]
+struct DiagEntry {
+    opcode: u32,
+    req_len: usize,
+    rsp_len: usize,
+}
+
+/// Fixed-size ring buffer of recent request/response opcodes and sizes.
+struct RingBuffer {
+    entries: [Option<DiagEntry>; RING_CAPACITY],
+    next: usize,
+}
+
+impl RingBuffer {
+    fn new() -> Self {
+        Self { entries: [None; RING_CAPACITY], next: 0 }
+    }
+
+    fn push(&mut self, entry: DiagEntry) {
+        self.entries[self.next] = Some(entry);
+        self.next = (self.next + 1) % self.entries.len();
+    }
+
+    /// Dump the buffer's contents to logcat, oldest first.
+    fn dump(&self) {
+        error!("TA worker fault; last {RING_CAPACITY} request(s) before the fault:");
+        for i in 0..self.entries.len() {
+            let idx = (self.next + i) % self.entries.len();
+            if let Some(e) = self.entries[idx] {
+                error!("  opcode={:#x} req_len={} rsp_len={}", e.opcode, e.req_len, e.rsp_len);
+            }
+        }
+    }
+}
+
+/// A [`TaWorker`] wrapped with automatic restart-on-fault and postmortem diagnostics.
+pub struct Supervisor<T> {
+    max_size: usize,
+    timeout: Duration,
+    build_ta: Arc<dyn Fn() -> T + Send + Sync>,
+    process: Arc<dyn Fn(&mut T, &[u8]) -> Vec<u8> + Send + Sync>,
+    opcode_of: Arc<dyn Fn(&[u8]) -> u32 + Send + Sync>,
+    worker: TaWorker,
+    ring: RingBuffer,
+    /// Raw bytes of the requests the caller has marked as handshake traffic (see
+    /// [`Supervisor::mark_handshake_done`]), replayed against a freshly rebuilt TA so it regains
+    /// the handshake state (e.g. boot info) the dead one had.
+    handshake: Vec<Vec<u8>>,
+    /// Once set, no further requests are appended to `handshake`: the caller has indicated that
+    /// startup handshake traffic is over and ordinary client calls must never be replayed.
+    handshake_done: bool,
+}
+
+impl<T: 'static> Supervisor<T> {
+    /// Build a supervised worker. `opcode_of` extracts a small diagnostic tag (e.g. the wire
+    /// opcode) from a request; it is used only for the ring buffer, never to interpret payloads.
+    pub fn new(
+        max_size: usize,
+        timeout: Duration,
+        build_ta: impl Fn() -> T + Send + Sync + 'static,
+        process: impl Fn(&mut T, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+        opcode_of: impl Fn(&[u8]) -> u32 + Send + Sync + 'static,
+    ) -> Self {
+        let build_ta = Arc::new(build_ta);
+        let process = Arc::new(process);
+        let worker = Self::spawn_worker(max_size, timeout, &build_ta, &process);
+        Self {
+            max_size,
+            timeout,
+            build_ta,
+            process,
+            opcode_of: Arc::new(opcode_of),
+            worker,
+            ring: RingBuffer::new(),
+            handshake: Vec::with_capacity(HANDSHAKE_CAPACITY),
+            handshake_done: false,
+        }
+    }
+
+    /// Mark the startup handshake as complete. Requests made before this call (up to
+    /// [`HANDSHAKE_CAPACITY`] of them) are replayed against a freshly rebuilt TA after a restart;
+    /// requests made from this point on are treated as ordinary client traffic and are never
+    /// replayed, however early they arrive.
+    ///
+    /// Callers that issue a fixed startup handshake (e.g. boot info / HAL info) should call this
+    /// immediately afterwards, before handing the channel to any client-facing service. Gatekeeper
+    /// currently has no startup handshake of its own, so it should call this immediately after
+    /// construction.
+    pub fn mark_handshake_done(&mut self) {
+        self.handshake_done = true;
+    }
+
+    fn spawn_worker(
+        max_size: usize,
+        timeout: Duration,
+        build_ta: &Arc<dyn Fn() -> T + Send + Sync>,
+        process: &Arc<dyn Fn(&mut T, &[u8]) -> Vec<u8> + Send + Sync>,
+    ) -> TaWorker {
+        let build_ta = Arc::clone(build_ta);
+        let process = Arc::clone(process);
+        TaWorker::new(max_size, timeout, move || (build_ta)(), move |ta, req| (process)(ta, req))
+    }
+
+    /// Call the TA. If the worker had died, this transparently rebuilds it, replays the recorded
+    /// handshake, and retries `req_data` against the fresh TA before returning.
+    pub fn call(&mut self, req_data: &[u8]) -> Result<Vec<u8>, Error> {
+        // Only requests made before the caller calls `mark_handshake_done` are recorded for
+        // replay; snapshot the buffer's length before possibly appending this request, so a
+        // restart doesn't replay the very request that's about to be retried below.
+        let handshake_len_before_this_call = self.handshake.len();
+        if !self.handshake_done && self.handshake.len() < HANDSHAKE_CAPACITY {
+            self.handshake.push(req_data.to_vec());
+        }
+
+        let opcode = (self.opcode_of)(req_data);
+        match self.worker.call(req_data) {
+            Ok(rsp) => {
+                self.ring.push(DiagEntry { opcode, req_len: req_data.len(), rsp_len: rsp.len() });
+                Ok(rsp)
+            }
+            Err(e @ (Error::Disconnected | Error::Timeout | Error::Desynced)) => {
+                // A timed-out or desynced TA hasn't necessarily exited -- it may be wedged in a
+                // loop, or merely running behind with a stale reply still sitting in the response
+                // channel -- but it can never be trusted to recover on its own, and leaving it
+                // running would desync (or hang) every subsequent call forever. Rebuilding drops
+                // the old worker's channels, so its thread (if it ever unwedges) finds them closed
+                // and exits instead of lingering.
+                warn!("TA worker is unhealthy ({e:?}); restarting it");
+                self.ring.dump();
+
+                self.worker =
+                    Self::spawn_worker(self.max_size, self.timeout, &self.build_ta, &self.process);
+                for handshake_req in &self.handshake[..handshake_len_before_this_call] {
+                    if let Err(e) = self.worker.call(handshake_req) {
+                        error!("failed to replay handshake request after TA restart: {e:?}");
+                        return Err(e);
+                    }
+                }
+
+                let rsp = self.worker.call(req_data)?;
+                self.ring.push(DiagEntry { opcode, req_len: req_data.len(), rsp_len: rsp.len() });
+                Ok(rsp)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// A fake TA that records which build generation it belongs to, so tests can tell which
+    /// requests were served before vs. after a restart.
+    struct FakeTa {
+        generation: u32,
+    }
+
+    #[test]
+    fn only_requests_before_mark_handshake_done_are_replayed_after_a_restart() {
+        let build_count = Arc::new(Mutex::new(0u32));
+        let seen = Arc::new(Mutex::new(Vec::<(u32, Vec<u8>)>::new()));
+
+        let build_count_for_build = Arc::clone(&build_count);
+        let seen_for_process = Arc::clone(&seen);
+
+        let mut supervisor = Supervisor::new(
+            1024,
+            Duration::from_millis(500),
+            move || {
+                let mut n = build_count_for_build.lock().unwrap();
+                *n += 1;
+                FakeTa { generation: *n }
+            },
+            move |ta: &mut FakeTa, req: &[u8]| {
+                seen_for_process.lock().unwrap().push((ta.generation, req.to_vec()));
+                // The first generation's TA crashes on this particular request, simulating a
+                // fault partway through ordinary client traffic; the rebuilt (second) generation
+                // handles it normally.
+                if req == b"client-request" && ta.generation == 1 {
+                    panic!("simulated TA crash");
+                }
+                vec![ta.generation as u8]
+            },
+            crate::opcode_of,
+        );
+
+        supervisor.call(b"boot-info").unwrap();
+        supervisor.mark_handshake_done();
+        // Issued after the handshake is marked done, so it must never be replayed, however early
+        // it arrives relative to the fault below -- this is the enroll(password)-style call that
+        // the old first-N-calls heuristic would have captured and replayed.
+        supervisor.call(b"client-secret").unwrap();
+
+        let rsp = supervisor.call(b"client-request").unwrap();
+        assert_eq!(rsp, vec![2], "the request should have been retried against the rebuilt TA");
+
+        let seen = seen.lock().unwrap();
+        let replayed_after_rebuild: Vec<&Vec<u8>> = seen
+            .iter()
+            .filter(|(generation, _)| *generation == 2)
+            .map(|(_, req)| req)
+            .collect();
+        assert_eq!(
+            replayed_after_rebuild,
+            vec![&b"boot-info".to_vec(), &b"client-request".to_vec()],
+            "only the marked handshake request should be replayed before the retried call, \
+             never the client-secret request"
+        );
+    }
+
+    #[test]
+    fn mark_handshake_done_with_no_prior_calls_replays_nothing() {
+        let build_count = Arc::new(Mutex::new(0u32));
+        let seen = Arc::new(Mutex::new(Vec::<(u32, Vec<u8>)>::new()));
+
+        let build_count_for_build = Arc::clone(&build_count);
+        let seen_for_process = Arc::clone(&seen);
+
+        let mut supervisor = Supervisor::new(
+            1024,
+            Duration::from_millis(500),
+            move || {
+                let mut n = build_count_for_build.lock().unwrap();
+                *n += 1;
+                FakeTa { generation: *n }
+            },
+            move |ta: &mut FakeTa, req: &[u8]| {
+                seen_for_process.lock().unwrap().push((ta.generation, req.to_vec()));
+                if req == b"client-request" && ta.generation == 1 {
+                    panic!("simulated TA crash");
+                }
+                vec![ta.generation as u8]
+            },
+            crate::opcode_of,
+        );
+
+        // A service with no startup handshake (e.g. Gatekeeper) should mark itself done
+        // immediately, so every subsequent client call is exempt from replay.
+        supervisor.mark_handshake_done();
+        supervisor.call(b"client-request").unwrap();
+
+        let seen = seen.lock().unwrap();
+        let replayed_after_rebuild =
+            seen.iter().filter(|(generation, _)| *generation == 2).count();
+        assert_eq!(replayed_after_rebuild, 1, "only the retried request itself, nothing replayed");
+    }
+
+    #[test]
+    fn call_rebuilds_the_worker_after_a_timeout() {
+        let build_count = Arc::new(Mutex::new(0u32));
+        let build_count_for_build = Arc::clone(&build_count);
+
+        let mut supervisor = Supervisor::new(
+            1024,
+            Duration::from_millis(50),
+            move || {
+                let mut n = build_count_for_build.lock().unwrap();
+                *n += 1;
+                FakeTa { generation: *n }
+            },
+            move |ta: &mut FakeTa, _req: &[u8]| {
+                // The first generation's TA is wedged (far slower than the call timeout) rather
+                // than dead; it never closes its channel, so only treating Disconnected as
+                // restart-worthy would leave every future call timing out forever.
+                if ta.generation == 1 {
+                    std::thread::sleep(Duration::from_millis(150));
+                }
+                vec![ta.generation as u8]
+            },
+            crate::opcode_of,
+        );
+
+        let rsp = supervisor.call(b"slow").unwrap();
+        assert_eq!(rsp, vec![2], "a timed-out call should be retried against a freshly rebuilt TA");
+    }
+
+    #[test]
+    fn call_rebuilds_the_worker_after_a_desync() {
+        let build_count = Arc::new(Mutex::new(0u32));
+        let build_count_for_build = Arc::clone(&build_count);
+
+        let mut supervisor = Supervisor::new(
+            1024,
+            Duration::from_millis(50),
+            move || {
+                let mut n = build_count_for_build.lock().unwrap();
+                *n += 1;
+                FakeTa { generation: *n }
+            },
+            move |ta: &mut FakeTa, _req: &[u8]| {
+                if ta.generation == 1 {
+                    std::thread::sleep(Duration::from_millis(150));
+                }
+                vec![ta.generation as u8]
+            },
+            crate::opcode_of,
+        );
+
+        // Manufacture a desync directly on the underlying worker, bypassing the supervisor: a
+        // call that times out still eventually replies, leaving a stale, unread response sitting
+        // in the channel with an ID that doesn't match the next call's.
+        assert_eq!(supervisor.worker.call(b"first"), Err(Error::Timeout));
+        std::thread::sleep(Duration::from_millis(200));
+
+        // The next call the supervisor makes reads back that stale reply and desyncs; the
+        // supervisor must treat that the same as a dead worker and rebuild rather than
+        // returning Desynced forever.
+        let rsp = supervisor.call(b"second").unwrap();
+        assert_eq!(rsp, vec![2], "a desynced call should be retried against a freshly rebuilt TA");
+    }
+}
@@ -0,0 +1,220 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! DICE (Device Identifier Composition Engine) support for the nonsecure KeyMint TA.
+//!
+//! This module builds a Boot Certificate Chain (BCC) rooted in a fixed Unique Device Secret (UDS),
+//! following the Open Profile for DICE, and hands it to the TA at construction time as its
+//! attestation root, so that test/compliance runs exercise an attestation chain with the same
+//! shape a real DICE-capable bootloader would produce. The boot state itself is still the fixed
+//! one sent by `kmr_hal_nonsecure::send_boot_info_and_attestation_id_info`.
+//!
+//! Only a single DICE layer is derived (representing "the firmware"), which is sufficient to
+//! give [`LocalTa`](crate::LocalTa) a non-trivial attestation root: `UDS -> CDI_0 -> leaf key`.
+
+use ciborium::cbor;
+use ciborium::value::Value;
+use coset::{
+    iana, CborSerializable, CoseKey, CoseKeyBuilder, CoseSign1, CoseSign1Builder, HeaderBuilder,
+};
+use kmr_crypto_boring::{ec::EcKeyPair, hkdf::hkdf_sha256, hmac::hmac_sha256};
+
+/// Fixed Unique Device Secret seed used for the insecure build. A real implementation must
+/// source this from one-time-programmable, hardware-bound storage rather than a constant.
+const UDS_SEED: [u8; 32] = [0x55; 32];
+
+/// CWT claim labels (map keys) used for the BCC certificate payload, per the Open Profile for DICE.
+mod label {
+    pub const ISSUER: i64 = 1;
+    pub const SUBJECT: i64 = 2;
+    pub const SUBJECT_PUBLIC_KEY: i64 = -4670552;
+    pub const CODE_HASH: i64 = -4670545;
+    pub const CONFIG_DESC: i64 = -4670548;
+    pub const AUTHORITY_HASH: i64 = -4670549;
+}
+
+/// A DICE CDI (Compound Device Identifier) pair, as produced by one layer of the DICE chain.
+struct Cdi {
+    attest: [u8; 32],
+    seal: [u8; 32],
+}
+
+/// Inputs that describe the code measured by one DICE layer.
+struct LayerInput<'a> {
+    code_hash: &'a [u8; 32],
+    config_desc: &'a [u8],
+    authority_hash: &'a [u8; 32],
+}
+
+/// Derive the next layer's CDI pair from the current layer's secret, by HMAC-ing the secret with
+/// the concatenation of the code hash, config descriptor and authority hash. `CDI_attest` and
+/// `CDI_seal` are domain-separated via distinct HMAC labels.
+fn derive_cdi(secret: &[u8; 32], input: &LayerInput) -> Cdi {
+    let mut msg = Vec::with_capacity(64 + input.config_desc.len());
+    msg.extend_from_slice(input.code_hash);
+    msg.extend_from_slice(input.config_desc);
+    msg.extend_from_slice(input.authority_hash);
+
+    Cdi {
+        attest: hmac_sha256(secret, b"CDI_attest", &msg),
+        seal: hmac_sha256(secret, b"CDI_seal", &msg),
+    }
+}
+
+/// Derive an Ed25519 key pair for a DICE layer from its `CDI_attest` value, via HKDF-SHA256.
+fn keypair_from_cdi_attest(cdi_attest: &[u8; 32]) -> EcKeyPair {
+    let seed = hkdf_sha256(cdi_attest, b"Key-Seed", 32);
+    EcKeyPair::from_ed25519_seed(&seed)
+}
+
+/// Build the CBOR `COSE_Key` for a public signing key, as carried in the UDS handover entry and
+/// as the subject public key of a layer certificate.
+fn public_cose_key(keypair: &EcKeyPair) -> CoseKey {
+    CoseKeyBuilder::new_okp_key()
+        .algorithm(iana::Algorithm::EdDSA)
+        .param(iana::OkpKeyParameter::Crv as i64, Value::from(iana::EllipticCurve::Ed25519 as u64))
+        .param(iana::OkpKeyParameter::X as i64, Value::Bytes(keypair.public_key_bytes()))
+        .build()
+}
+
+/// Build a CBOR/COSE-signed CWT certificate for one DICE layer: the payload carries the layer's
+/// subject public key, code hash and config descriptor, and is signed with the previous layer's
+/// private key.
+fn build_layer_cert(
+    signing_key: &EcKeyPair,
+    subject_keypair: &EcKeyPair,
+    input: &LayerInput,
+) -> Vec<u8> {
+    let payload = cbor!({
+        label::ISSUER => "KM nonsecure DICE",
+        label::SUBJECT => "KM nonsecure DICE firmware",
+        label::SUBJECT_PUBLIC_KEY => public_cose_key(subject_keypair)
+            .to_vec()
+            .expect("failed to serialize COSE_Key"),
+        label::CODE_HASH => Value::Bytes(input.code_hash.to_vec()),
+        label::CONFIG_DESC => Value::Bytes(input.config_desc.to_vec()),
+        label::AUTHORITY_HASH => Value::Bytes(input.authority_hash.to_vec()),
+    })
+    .expect("failed to build DICE certificate payload");
+
+    let mut payload_bytes = Vec::new();
+    ciborium::into_writer(&payload, &mut payload_bytes).expect("failed to encode DICE payload");
+
+    let protected = HeaderBuilder::new().algorithm(iana::Algorithm::EdDSA).build();
+    let sign1 = CoseSign1Builder::new()
+        .protected(protected)
+        .payload(payload_bytes)
+        .create_signature(&[], |to_sign| signing_key.sign_ed25519(to_sign))
+        .build();
+    sign1.to_vec().expect("failed to serialize COSE_Sign1")
+}
+
+/// The result of assembling the (single-layer) BCC handover for the insecure build: the CBOR
+/// array `[UDS_public_COSE_Key, cert_0]`, plus the leaf layer's private key that the TA should use
+/// as its attestation signing root.
+pub struct BccHandover {
+    pub bcc: Vec<u8>,
+    pub leaf_private_key: EcKeyPair,
+}
+
+/// Build the DICE BCC handover used as the attestation root for the nonsecure KeyMint TA.
+///
+/// This runs a single DICE layer step ("the firmware") starting from a fixed UDS seed, rather
+/// than relying on the previously hardcoded/synthetic attestation material.
+pub fn build_bcc_handover() -> BccHandover {
+    let uds_keypair = EcKeyPair::from_ed25519_seed(&UDS_SEED);
+
+    // A fixed measurement of "the firmware" for the insecure build; a real bootloader would
+    // measure the actual next-stage image, its configuration and its signer.
+    let input = LayerInput {
+        code_hash: &[0x11; 32],
+        config_desc: b"KM nonsecure firmware v1",
+        authority_hash: &[0x22; 32],
+    };
+
+    let cdi = derive_cdi(&UDS_SEED, &input);
+    let leaf_keypair = keypair_from_cdi_attest(&cdi.attest);
+    let cert = build_layer_cert(&uds_keypair, &leaf_keypair, &input);
+
+    let uds_public = public_cose_key(&uds_keypair)
+        .to_vec()
+        .expect("failed to serialize UDS public COSE_Key");
+
+    let bcc = Value::Array(vec![Value::Bytes(uds_public), Value::Bytes(cert)]);
+    let mut bcc_bytes = Vec::new();
+    ciborium::into_writer(&bcc, &mut bcc_bytes).expect("failed to encode BCC handover");
+
+    // `cdi.seal` is not consumed further by this single-layer chain, but is derived here (rather
+    // than dropped) so that a future layer can fold it in, matching the Open Profile for DICE.
+    let _ = cdi.seal;
+
+    BccHandover { bcc: bcc_bytes, leaf_private_key: leaf_keypair }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_bcc_handover_is_deterministic() {
+        let first = build_bcc_handover();
+        let second = build_bcc_handover();
+        assert_eq!(first.bcc, second.bcc);
+        assert_eq!(
+            first.leaf_private_key.public_key_bytes(),
+            second.leaf_private_key.public_key_bytes(),
+            "a fixed UDS seed must derive the same leaf key every time"
+        );
+    }
+
+    #[test]
+    fn bcc_decodes_to_the_uds_public_key_followed_by_one_cert() {
+        let handover = build_bcc_handover();
+        let value: Value =
+            ciborium::from_reader(handover.bcc.as_slice()).expect("BCC handover must be valid CBOR");
+        let entries = match value {
+            Value::Array(entries) => entries,
+            other => panic!("expected a CBOR array, got {other:?}"),
+        };
+        assert_eq!(entries.len(), 2, "expected [UDS_pub_COSE_Key, cert_0]");
+        let uds_public = public_cose_key(&EcKeyPair::from_ed25519_seed(&UDS_SEED))
+            .to_vec()
+            .expect("failed to serialize UDS public COSE_Key");
+        assert_eq!(entries[0], Value::Bytes(uds_public), "entry 0 must be the UDS public COSE_Key");
+    }
+
+    #[test]
+    fn cdi_attest_and_cdi_seal_are_domain_separated() {
+        let input = LayerInput {
+            code_hash: &[0x11; 32],
+            config_desc: b"test",
+            authority_hash: &[0x22; 32],
+        };
+        let cdi = derive_cdi(&UDS_SEED, &input);
+        assert_ne!(cdi.attest, cdi.seal);
+    }
+
+    #[test]
+    fn derive_cdi_is_sensitive_to_the_config_descriptor() {
+        let base =
+            LayerInput { code_hash: &[0x11; 32], config_desc: b"v1", authority_hash: &[0x22; 32] };
+        let changed =
+            LayerInput { code_hash: &[0x11; 32], config_desc: b"v2", authority_hash: &[0x22; 32] };
+        let cdi_base = derive_cdi(&UDS_SEED, &base);
+        let cdi_changed = derive_cdi(&UDS_SEED, &changed);
+        assert_ne!(cdi_base.attest, cdi_changed.attest);
+    }
+}
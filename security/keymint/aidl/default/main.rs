@@ -26,7 +26,11 @@
 use kmr_hal::{register_binder_services, HalServiceError, SerializedChannel, ALL_HALS};
 use log::{error, info, warn};
 use std::ops::DerefMut;
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ta_worker::Supervisor;
+
+mod dice;
 
 /// Name of KeyMint binder device instance.
 static SERVICE_INSTANCE: &str = "default";
@@ -65,6 +69,10 @@ fn inner_main() -> Result<(), HalServiceError> {
         error!("Failed to send HAL info: {:?}", e);
     }
 
+    // The startup handshake is now complete: no request made from here on (i.e. no client RPC)
+    // may be replayed against a TA rebuilt after a restart.
+    channel.lock().unwrap().mark_handshake_done();
+
     register_binder_services(&channel, ALL_HALS, SERVICE_INSTANCE)?;
 
     binder::ProcessState::join_thread_pool();
@@ -73,40 +81,50 @@ fn inner_main() -> Result<(), HalServiceError> {
 }
 
 /// Implementation of the KeyMint TA that runs locally in-process (and which is therefore
-/// insecure).
-#[derive(Debug)]
+/// insecure). The TA thread is supervised: if it dies, it is transparently rebuilt rather than
+/// taking the whole HAL service down with it.
 pub struct LocalTa {
-    in_tx: mpsc::Sender<Vec<u8>>,
-    out_rx: mpsc::Receiver<Vec<u8>>,
+    supervisor: Supervisor<kmr_ta::KeyMintTa>,
 }
 
 impl LocalTa {
-    /// Create a new instance.
+    /// How long to wait for the TA to reply before treating it as wedged.
+    const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Create a new instance. A fresh DICE BCC handover (see [`dice`]) is built as the TA's
+    /// attestation root, both now and again on every restart should the TA thread die.
     pub fn new() -> Self {
-        // Create a pair of channels to communicate with the TA thread.
-        let (in_tx, in_rx) = mpsc::channel();
-        let (out_tx, out_rx) = mpsc::channel();
-
-        // The TA code expects to run single threaded, so spawn a thread to run it in.
-        std::thread::spawn(move || {
-            let mut ta = kmr_ta_nonsecure::build_ta();
-            loop {
-                let req_data: Vec<u8> = in_rx.recv().expect("failed to receive next req");
-                let rsp_data = ta.process(&req_data);
-                out_tx.send(rsp_data).expect("failed to send out rsp");
-            }
-        });
-        Self { in_tx, out_rx }
+        let supervisor = Supervisor::new(
+            Self::MAX_SIZE,
+            Self::CALL_TIMEOUT,
+            || {
+                let bcc_handover = dice::build_bcc_handover();
+                kmr_ta_nonsecure::build_ta(bcc_handover.bcc, bcc_handover.leaf_private_key)
+            },
+            |ta, req_data| ta.process(req_data),
+            ta_worker::opcode_of,
+        );
+        Self { supervisor }
+    }
+
+    /// Mark the startup handshake as complete; see [`Supervisor::mark_handshake_done`].
+    pub fn mark_handshake_done(&mut self) {
+        self.supervisor.mark_handshake_done();
     }
 }
 
 impl SerializedChannel for LocalTa {
-    const MAX_SIZE: usize = usize::MAX;
+    const MAX_SIZE: usize = 64 * 1024;
 
     fn execute(&mut self, req_data: &[u8]) -> binder::Result<Vec<u8>> {
-        self.in_tx
-            .send(req_data.to_vec())
-            .expect("failed to send in request");
-        Ok(self.out_rx.recv().expect("failed to receive response"))
+        self.supervisor.call(req_data).map_err(|e| match e {
+            ta_worker::Error::RequestTooLarge => {
+                binder::Status::new_exception(binder::ExceptionCode::ILLEGAL_ARGUMENT, None)
+            }
+            ta_worker::Error::Timeout => binder::Status::from(binder::StatusCode::TIMED_OUT),
+            ta_worker::Error::Disconnected | ta_worker::Error::Desynced => {
+                binder::Status::from(binder::StatusCode::DEAD_OBJECT)
+            }
+        })
     }
 }